@@ -1,11 +1,12 @@
 use std::{
-    collections::{HashSet, VecDeque},
-    ffi::OsStr,
+    collections::{HashMap, HashSet, VecDeque},
+    ffi::{OsStr, OsString},
     fs::File,
-    io::Write,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{BufRead, BufReader, IsTerminal, Write},
     num::NonZero,
-    path::PathBuf,
-    process::Command,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
     sync::{Arc, Condvar, Mutex},
     thread::{available_parallelism, sleep},
     time::Duration,
@@ -20,11 +21,12 @@ use nix::sys::signal::{SigHandler, Signal, signal};
 use std::os::unix::process::CommandExt;
 
 use clap::Parser;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use walkdir::WalkDir;
 
 #[derive(Default)]
 struct State {
-    jobs: VecDeque<(PathBuf, PathBuf)>,
+    jobs: VecDeque<Job>,
     cancel: bool,
     done: bool,
 }
@@ -36,6 +38,871 @@ enum ArgMode {
     Custom,
 }
 
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum VerifyMode {
+    /// Delete the source as soon as ffmpeg exits zero (legacy behaviour).
+    Off,
+    /// Require a non-empty output file.
+    Exists,
+    /// Require the output to be probeable/decodable by ffprobe.
+    Decode,
+    /// Also require the output's frame count / duration to match the source.
+    Framecount,
+}
+
+/// Decide whether `output` faithfully represents `source` before the source is
+/// deleted. The stricter modes build on the cheaper ones.
+fn verify_output(mode: VerifyMode, source: &Path, output: &Path) -> bool {
+    let non_empty = || output.metadata().map(|m| m.len() > 0).unwrap_or(false);
+
+    match mode {
+        VerifyMode::Off => true,
+        VerifyMode::Exists => non_empty(),
+        VerifyMode::Decode => non_empty() && ffprobe_decodable(output),
+        VerifyMode::Framecount => {
+            non_empty() && ffprobe_decodable(output) && counts_match(source, output)
+        }
+    }
+}
+
+/// Whether ffprobe can read a video stream out of `path`.
+fn ffprobe_decodable(path: &Path) -> bool {
+    let output = Command::new("ffprobe")
+        .args([
+            OsStr::new("-v"),
+            OsStr::new("error"),
+            OsStr::new("-select_streams"),
+            OsStr::new("v:0"),
+            OsStr::new("-show_entries"),
+            OsStr::new("stream=codec_type"),
+            OsStr::new("-of"),
+            OsStr::new("csv=p=0"),
+            path.as_os_str(),
+        ])
+        .output();
+
+    matches!(output, Ok(out) if out.status.success() && !out.stdout.is_empty())
+}
+
+/// Compare source and output by frame count, falling back to duration, within
+/// a small tolerance so lossy container rounding doesn't trip the check.
+fn counts_match(source: &Path, output: &Path) -> bool {
+    if let (Some(a), Some(b)) = (
+        ffprobe_entry(source, true, "nb_frames"),
+        ffprobe_entry(output, true, "nb_frames"),
+    ) {
+        if a > 0.0 && b > 0.0 {
+            return (a - b).abs() <= (a * 0.01).max(1.0);
+        }
+    }
+
+    match (
+        ffprobe_entry(source, false, "duration"),
+        ffprobe_entry(output, false, "duration"),
+    ) {
+        (Some(a), Some(b)) => (a - b).abs() <= 0.5,
+        _ => false,
+    }
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum MetadataMode {
+    /// Carry the source's EXIF/ICC/XMP over to the output.
+    Keep,
+    /// Scrub all metadata from the output.
+    Strip,
+}
+
+/// Keep or strip image metadata on the freshly-encoded output, shelling out to
+/// exiftool the way pict-rs drives external tools. Runs before the source is
+/// deleted so `keep` still has something to copy from.
+fn apply_metadata(mode: MetadataMode, source: &Path, output: &Path) {
+    let mut command = Command::new("exiftool");
+
+    match mode {
+        MetadataMode::Keep => {
+            command.args([
+                OsStr::new("-overwrite_original"),
+                OsStr::new("-TagsFromFile"),
+                source.as_os_str(),
+                OsStr::new("-all:all"),
+                OsStr::new("-icc_profile"),
+                output.as_os_str(),
+            ]);
+        }
+        MetadataMode::Strip => {
+            command.args([
+                OsStr::new("-overwrite_original"),
+                OsStr::new("-all="),
+                output.as_os_str(),
+            ]);
+        }
+    }
+
+    match command.status() {
+        Ok(status) if status.success() => {}
+        _ => eprintln!("Metadata step failed for {:?}", output),
+    }
+}
+
+/// One encoder recipe: a command template split into argv tokens and the
+/// output extension its `{output}` should carry. `{input}` and `{output}`
+/// are substituted per job, pict-rs style.
+struct CustomProfile {
+    template: Vec<String>,
+    out_ext: String,
+}
+
+impl CustomProfile {
+    /// Substitute the per-job placeholders, keeping the paths as `OsStr`
+    /// when a token is exactly a placeholder so non-UTF-8 names survive.
+    fn build(&self, input: &Path, output: &Path) -> Vec<OsString> {
+        let mut argv = Vec::with_capacity(self.template.len());
+
+        for token in &self.template {
+            match token.as_str() {
+                "{input}" => argv.push(input.as_os_str().to_owned()),
+                "{output}" => argv.push(output.as_os_str().to_owned()),
+                other => argv.push(OsString::from(
+                    other
+                        .replace("{input}", &input.to_string_lossy())
+                        .replace("{output}", &output.to_string_lossy()),
+                )),
+            }
+        }
+
+        argv
+    }
+}
+
+/// The `Custom` argument mode: a default recipe (from the positional
+/// template) plus optional per-extension profiles loaded from a TOML file.
+struct CustomConfig {
+    profiles: HashMap<String, CustomProfile>,
+    default: Option<CustomProfile>,
+}
+
+impl CustomConfig {
+    /// Load the custom profiles. A bad `--config` path or malformed TOML is a
+    /// user error, so it surfaces as an `Err` string rather than a panic.
+    fn load(
+        template: Option<&str>,
+        config: Option<&PathBuf>,
+        default_ext: &str,
+    ) -> Result<CustomConfig, String> {
+        let mut profiles = HashMap::new();
+
+        if let Some(path) = config {
+            let text = std::fs::read_to_string(path)
+                .map_err(|e| format!("failed to read config {path:?}: {e}"))?;
+            let value: toml::Value =
+                toml::from_str(&text).map_err(|e| format!("invalid TOML in {path:?}: {e}"))?;
+            let table = value
+                .as_table()
+                .ok_or_else(|| format!("config {path:?} must be a TOML table"))?;
+
+            for (ext, entry) in table {
+                let entry = entry
+                    .as_table()
+                    .ok_or_else(|| format!("profile `{ext}` must be a TOML table"))?;
+                let template = entry
+                    .get("template")
+                    .and_then(toml::Value::as_str)
+                    .ok_or_else(|| format!("profile `{ext}` is missing a `template` string"))?;
+                let out_ext = entry
+                    .get("ext")
+                    .and_then(toml::Value::as_str)
+                    .unwrap_or(default_ext);
+
+                profiles.insert(
+                    ext.to_ascii_lowercase(),
+                    CustomProfile {
+                        template: split_template(template),
+                        out_ext: out_ext.to_owned(),
+                    },
+                );
+            }
+        }
+
+        let default = template.map(|template| CustomProfile {
+            template: split_template(template),
+            out_ext: default_ext.to_owned(),
+        });
+
+        Ok(CustomConfig { profiles, default })
+    }
+
+    /// The recipe for a source extension: an exact profile match wins,
+    /// otherwise the default template (if any).
+    fn profile(&self, ext: &str) -> Option<&CustomProfile> {
+        self.profiles
+            .get(&ext.to_ascii_lowercase())
+            .or(self.default.as_ref())
+    }
+
+    /// Output extension a source file should be transcoded to.
+    fn out_ext<'a>(&'a self, ext: &str, fallback: &'a str) -> &'a str {
+        self.profile(ext).map(|p| p.out_ext.as_str()).unwrap_or(fallback)
+    }
+}
+
+/// Split a command template into argv tokens, honouring single and double
+/// quotes so a profile can embed spaces (e.g. a filter graph).
+fn split_template(s: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut started = false;
+    let mut single = false;
+    let mut double = false;
+
+    for c in s.chars() {
+        match c {
+            '\'' if !double => {
+                single = !single;
+                started = true;
+            }
+            '"' if !single => {
+                double = !double;
+                started = true;
+            }
+            c if c.is_whitespace() && !single && !double => {
+                if started {
+                    tokens.push(std::mem::take(&mut current));
+                    started = false;
+                }
+            }
+            c => {
+                current.push(c);
+                started = true;
+            }
+        }
+    }
+
+    if started {
+        tokens.push(current);
+    }
+
+    tokens
+}
+
+/// A single unit of work for the pool: one whole file, or one `[start,end)`
+/// chunk of a video that shares a [`ChunkGroup`] with its siblings.
+struct Job {
+    input: PathBuf,
+    output: PathBuf,
+    chunk: Option<Chunk>,
+}
+
+struct Chunk {
+    group: Arc<ChunkGroup>,
+    start: f64,
+    end: Option<f64>,
+}
+
+/// Shared state for every chunk carved out of one source video. The worker
+/// that finishes the last chunk stitches the segments back together with the
+/// concat demuxer and removes the original.
+struct ChunkGroup {
+    source: PathBuf,
+    output: PathBuf,
+    list: PathBuf,
+    segments: Vec<PathBuf>,
+    remaining: Mutex<usize>,
+    failed: Mutex<bool>,
+    verify: VerifyMode,
+    crf: String,
+}
+
+/// Build the encode argv for one video chunk. Uses input-side `-ss` (before
+/// `-i`) so ffmpeg fast-seeks to the keyframe preceding the cut instead of
+/// decoding from zero every time — otherwise total decode cost is quadratic in
+/// the chunk count. The forced closed GOP below means the re-encoded boundary
+/// lands cleanly, and `-t` (chunk length) keeps the trim relative to the seek.
+fn chunk_video_args(job: &Job) -> Vec<OsString> {
+    let chunk = job.chunk.as_ref().unwrap();
+
+    let mut args: Vec<OsString> = ["-hide_banner", "-n", "-ss"]
+        .iter()
+        .map(OsString::from)
+        .collect();
+
+    args.push(OsString::from(chunk.start.to_string()));
+
+    args.push(OsString::from("-i"));
+    args.push(job.input.as_os_str().to_owned());
+
+    if let Some(end) = chunk.end {
+        args.push(OsString::from("-t"));
+        args.push(OsString::from((end - chunk.start).to_string()));
+    }
+
+    args.push(OsString::from("-c:v"));
+    args.push(OsString::from("libvpx-vp9"));
+    args.push(OsString::from("-crf"));
+    args.push(OsString::from(&chunk.group.crf));
+
+    for arg in [
+        "-b:v",
+        "0",
+        "-b:a",
+        "128k",
+        "-c:a",
+        "libopus",
+        "-row-mt",
+        "1",
+        // Keep every segment self-contained: a forced closed GOP starts the
+        // chunk on a keyframe so the concat demuxer can stream-copy.
+        "-g",
+        "240",
+        "-keyint_min",
+        "240",
+        "-auto-alt-ref",
+        "0",
+    ] {
+        args.push(OsString::from(arg));
+    }
+
+    args.push(job.output.as_os_str().to_owned());
+    args
+}
+
+/// Record that one chunk finished, `ok` telling whether its encode succeeded.
+/// The worker that drains the last chunk finalizes the group: stitch on full
+/// success, otherwise clean up and keep the source.
+fn complete_chunk(chunk: &Chunk, ok: bool) {
+    let group = &chunk.group;
+
+    if !ok {
+        *group.failed.lock().unwrap() = true;
+    }
+
+    let remaining = {
+        let mut remaining = group.remaining.lock().unwrap();
+        *remaining -= 1;
+        *remaining
+    };
+
+    if remaining != 0 {
+        return;
+    }
+
+    // A failed chunk poisons the whole source: drop the orphaned segments and
+    // list, keep the original intact, and surface which source it was.
+    if *group.failed.lock().unwrap() {
+        for segment in &group.segments {
+            std::fs::remove_file(segment).ok();
+        }
+        std::fs::remove_file(&group.list).ok();
+        eprintln!("Chunk encode failed, keeping source {:?}", group.source);
+        return;
+    }
+
+    // The list and segments are co-located, and the concat demuxer resolves
+    // relative entries against the list file's own directory — so reference
+    // each segment by basename, escaping any `'` the way the demuxer expects.
+    let mut list = String::new();
+    for segment in &group.segments {
+        let name = segment
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .replace('\'', "'\\''");
+        list.push_str(&format!("file '{name}'\n"));
+    }
+    std::fs::write(&group.list, list).unwrap();
+
+    let status = Command::new("ffmpeg")
+        .args([
+            OsStr::new("-hide_banner"),
+            OsStr::new("-n"),
+            OsStr::new("-f"),
+            OsStr::new("concat"),
+            OsStr::new("-safe"),
+            OsStr::new("0"),
+            OsStr::new("-i"),
+            group.list.as_os_str(),
+            OsStr::new("-c"),
+            OsStr::new("copy"),
+            group.output.as_os_str(),
+        ])
+        .status()
+        .unwrap();
+
+    if !status.success() {
+        eprintln!("Failed to concat {:?}", group.output);
+        // Don't leak the segments, list, or a half-written output.
+        for segment in &group.segments {
+            std::fs::remove_file(segment).ok();
+        }
+        std::fs::remove_file(&group.list).ok();
+        std::fs::remove_file(&group.output).ok();
+        return;
+    }
+
+    for segment in &group.segments {
+        std::fs::remove_file(segment).ok();
+    }
+    std::fs::remove_file(&group.list).ok();
+
+    // Only destroy the source once the stitched output checks out.
+    if verify_output(group.verify, &group.source, &group.output) {
+        std::fs::remove_file(&group.source).unwrap();
+        println!("Stitched {:?}", group.output);
+    } else {
+        eprintln!("Verification failed, keeping source {:?}", group.source);
+        std::fs::remove_file(&group.output).ok();
+    }
+}
+
+/// Run a fast scene-change pass and return the cut timestamps (seconds).
+fn detect_scene_cuts(input: &Path, thresh: f64) -> Vec<f64> {
+    let filter = OsString::from(format!("select='gt(scene,{thresh})',showinfo"));
+
+    let output = Command::new("ffmpeg")
+        .args([
+            OsStr::new("-hide_banner"),
+            OsStr::new("-i"),
+            input.as_os_str(),
+            OsStr::new("-filter:v"),
+            filter.as_os_str(),
+            OsStr::new("-f"),
+            OsStr::new("null"),
+            OsStr::new("-"),
+        ])
+        .output()
+        .unwrap();
+
+    let mut cuts = Vec::new();
+    let text = String::from_utf8_lossy(&output.stderr);
+
+    for line in text.lines() {
+        if let Some(rest) = line.split("pts_time:").nth(1) {
+            let value: String = rest
+                .chars()
+                .take_while(|c| c.is_ascii_digit() || *c == '.')
+                .collect();
+
+            if let Ok(t) = value.parse::<f64>() {
+                cuts.push(t);
+            }
+        }
+    }
+
+    cuts
+}
+
+/// Query a single numeric stream/format field with ffprobe.
+fn ffprobe_entry(input: &Path, stream: bool, entry: &str) -> Option<f64> {
+    let kind = if stream {
+        format!("stream={entry}")
+    } else {
+        format!("format={entry}")
+    };
+
+    let output = Command::new("ffprobe")
+        .args([
+            OsStr::new("-v"),
+            OsStr::new("error"),
+            OsStr::new("-select_streams"),
+            OsStr::new("v:0"),
+            OsStr::new("-show_entries"),
+            OsStr::new(&kind),
+            OsStr::new("-of"),
+            OsStr::new("default=nw=1:nk=1"),
+            input.as_os_str(),
+        ])
+        .output()
+        .ok()?;
+
+    let text = String::from_utf8_lossy(&output.stdout);
+    let line = text.lines().next()?.trim();
+
+    if let Some((num, den)) = line.split_once('/') {
+        let num: f64 = num.parse().ok()?;
+        let den: f64 = den.parse().ok()?;
+        (den != 0.0).then(|| num / den)
+    } else {
+        line.parse().ok()
+    }
+}
+
+/// Encode a one-second probe at `crf` and score it against the source segment
+/// with `libvmaf`, returning the pooled mean VMAF. `None` if any step fails.
+fn probe_score(input: &Path, start: f64, crf: u32, dir: &Path) -> Option<f64> {
+    let reference = dir.join("reference.mkv");
+    let distorted = dir.join(format!("crf{crf}.webm"));
+    let log = dir.join("vmaf.json");
+
+    // Lossless reference cut so VMAF compares against real source frames.
+    let ok = Command::new("ffmpeg")
+        .args([
+            OsStr::new("-y"),
+            OsStr::new("-hide_banner"),
+            OsStr::new("-ss"),
+            OsStr::new(&start.to_string()),
+            OsStr::new("-i"),
+            input.as_os_str(),
+            OsStr::new("-t"),
+            OsStr::new("1"),
+            OsStr::new("-c:v"),
+            OsStr::new("ffv1"),
+            OsStr::new("-an"),
+            reference.as_os_str(),
+        ])
+        .output()
+        .ok()?
+        .status
+        .success();
+
+    if !ok {
+        return None;
+    }
+
+    let ok = Command::new("ffmpeg")
+        .args([
+            OsStr::new("-y"),
+            OsStr::new("-hide_banner"),
+            OsStr::new("-i"),
+            reference.as_os_str(),
+            OsStr::new("-c:v"),
+            OsStr::new("libvpx-vp9"),
+            OsStr::new("-crf"),
+            OsStr::new(&crf.to_string()),
+            OsStr::new("-b:v"),
+            OsStr::new("0"),
+            OsStr::new("-row-mt"),
+            OsStr::new("1"),
+            OsStr::new("-an"),
+            distorted.as_os_str(),
+        ])
+        .output()
+        .ok()?
+        .status
+        .success();
+
+    if !ok {
+        return None;
+    }
+
+    let lavfi = OsString::from(format!(
+        "libvmaf=log_fmt=json:log_path={}",
+        log.to_string_lossy()
+    ));
+
+    let ok = Command::new("ffmpeg")
+        .args([
+            OsStr::new("-hide_banner"),
+            OsStr::new("-i"),
+            distorted.as_os_str(),
+            OsStr::new("-i"),
+            reference.as_os_str(),
+            OsStr::new("-lavfi"),
+            lavfi.as_os_str(),
+            OsStr::new("-f"),
+            OsStr::new("null"),
+            OsStr::new("-"),
+        ])
+        .output()
+        .ok()?
+        .status
+        .success();
+
+    if !ok {
+        return None;
+    }
+
+    let text = std::fs::read_to_string(&log).ok()?;
+    parse_vmaf_mean(&text)
+}
+
+/// Pull the pooled mean VMAF out of a `libvmaf` JSON log. Anchored on
+/// `pooled_metrics` so per-frame `vmaf`/`mean` entries can't be mistaken for
+/// the pooled score.
+fn parse_vmaf_mean(json: &str) -> Option<f64> {
+    let pooled = &json[json.find("\"pooled_metrics\"")?..];
+    let rest = &pooled[pooled.find("\"vmaf\"")?..];
+    let after = &rest[rest.find("\"mean\"")? + "\"mean\"".len()..];
+    let start = after.find(|c: char| c == '-' || c.is_ascii_digit())?;
+    let number: String = after[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == '.' || *c == '-')
+        .collect();
+    number.parse().ok()
+}
+
+/// CRF search bounds for target-quality mode.
+const CRF_MIN: u32 = 20;
+const CRF_MAX: u32 = 50;
+
+/// Pick the CRF that should land `input` on `target` VMAF: probe a handful of
+/// clips at bracketing CRF values, then linearly interpolate score-vs-CRF.
+fn select_crf(input: &Path, target: f64) -> u32 {
+    let duration = ffprobe_entry(input, false, "duration").unwrap_or(0.0);
+
+    let probes: Vec<f64> = (1..=4)
+        .map(|i| duration * i as f64 / 5.0)
+        .filter(|t| *t > 0.0)
+        .collect();
+    let probes = if probes.is_empty() { vec![0.0] } else { probes };
+
+    // Key the scratch dir per-input so concurrent workers don't clobber each
+    // other's reference/distorted/log files (and so `remove_dir_all` can't
+    // delete another probe in flight).
+    let mut hasher = DefaultHasher::new();
+    input.hash(&mut hasher);
+    let dir = std::env::temp_dir().join(format!(
+        "ffpack-vmaf-{}-{:016x}",
+        std::process::id(),
+        hasher.finish()
+    ));
+    std::fs::create_dir_all(&dir).ok();
+
+    // (crf, mean score) points, CRF ascending; score falls as CRF rises.
+    let mut points = Vec::new();
+
+    for &crf in &[CRF_MIN, 30, 40, CRF_MAX] {
+        let mut scores = Vec::new();
+        for &start in &probes {
+            if let Some(score) = probe_score(input, start, crf, &dir) {
+                scores.push(score);
+            }
+        }
+
+        if !scores.is_empty() {
+            let mean = scores.iter().sum::<f64>() / scores.len() as f64;
+            points.push((crf, mean));
+        }
+    }
+
+    std::fs::remove_dir_all(&dir).ok();
+
+    interpolate_crf(&points, target)
+}
+
+/// Solve the (CRF, score) samples for the CRF hitting `target`, clamped to the
+/// search range. Falls back to the hardcoded 30 when probing produced nothing.
+fn interpolate_crf(points: &[(u32, f64)], target: f64) -> u32 {
+    if points.is_empty() {
+        return 30;
+    }
+
+    for pair in points.windows(2) {
+        let (c0, s0) = pair[0];
+        let (c1, s1) = pair[1];
+
+        if s0 >= target && target >= s1 && (s0 - s1).abs() > f64::EPSILON {
+            let crf = c0 as f64 + (c1 - c0) as f64 * (s0 - target) / (s0 - s1);
+            return (crf.round() as u32).clamp(CRF_MIN, CRF_MAX);
+        }
+    }
+
+    // Target outside the probed range: take the closest bound.
+    if target > points[0].1 {
+        points[0].0
+    } else {
+        points[points.len() - 1].0
+    }
+}
+
+/// Plan chunk boundaries for one source: detect scene cuts, then merge them so
+/// every chunk spans at least `min_frames` frames. Returns `[start,end)`
+/// ranges with the final chunk running to EOF (`None`).
+fn plan_chunks(input: &Path, thresh: f64, min_frames: usize) -> Vec<(f64, Option<f64>)> {
+    let fps = ffprobe_entry(input, true, "r_frame_rate").unwrap_or(30.0);
+    let min_span = min_frames as f64 / fps.max(1.0);
+
+    let mut boundaries = vec![0.0];
+    let mut last = 0.0;
+
+    for cut in detect_scene_cuts(input, thresh) {
+        if cut - last >= min_span {
+            boundaries.push(cut);
+            last = cut;
+        }
+    }
+
+    // The final chunk runs to EOF: fold it into its predecessor if too short.
+    if let Some(duration) = ffprobe_entry(input, false, "duration") {
+        if boundaries.len() > 1 && duration - last < min_span {
+            boundaries.pop();
+        }
+    }
+
+    let mut chunks = Vec::with_capacity(boundaries.len());
+
+    for (i, &start) in boundaries.iter().enumerate() {
+        let end = boundaries.get(i + 1).copied();
+        chunks.push((start, end));
+    }
+
+    chunks
+}
+
+/// Spawn ffmpeg with its stdout wired to `-progress pipe:1`, advancing `bar`
+/// from the parsed `out_time`/`speed` stream while it runs. `duration` (seconds,
+/// pre-probed) sets the bar length so the percentage is real.
+fn run_with_progress(mut command: Command, bar: &ProgressBar, duration: f64) -> Output {
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+
+    let mut child = command.spawn().unwrap();
+    let stdout = child.stdout.take().unwrap();
+
+    if duration > 0.0 {
+        bar.set_length(duration.ceil() as u64);
+    }
+
+    for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+        if let Some(value) = line
+            .strip_prefix("out_time_us=")
+            .or_else(|| line.strip_prefix("out_time_ms="))
+        {
+            if let Ok(us) = value.trim().parse::<f64>() {
+                bar.set_position((us / 1_000_000.0) as u64);
+            }
+        } else if let Some(value) = line.strip_prefix("speed=") {
+            bar.set_message(format!("speed={}", value.trim()));
+        }
+    }
+
+    let output = child.wait_with_output().unwrap();
+
+    if let Some(length) = bar.length() {
+        bar.set_position(length);
+    }
+
+    output
+}
+
+/// Maximum Hamming distance at which two fingerprints count as near-duplicates.
+const DEDUPE_THRESHOLD: u32 = 5;
+
+/// Perceptual fingerprint of a file: decode a downscaled 9×8 grayscale frame
+/// through ffmpeg and dHash it into a 64-bit value. Videos go through the
+/// `thumbnail` filter first so the hash reflects a representative frame.
+fn perceptual_hash(path: &Path, video: bool) -> Option<u64> {
+    let filter = if video {
+        "thumbnail,scale=9:8,format=gray"
+    } else {
+        "scale=9:8,format=gray"
+    };
+
+    let output = Command::new("ffmpeg")
+        .args([
+            OsStr::new("-hide_banner"),
+            OsStr::new("-i"),
+            path.as_os_str(),
+            OsStr::new("-vf"),
+            OsStr::new(filter),
+            OsStr::new("-frames:v"),
+            OsStr::new("1"),
+            OsStr::new("-f"),
+            OsStr::new("rawvideo"),
+            OsStr::new("-pix_fmt"),
+            OsStr::new("gray"),
+            OsStr::new("-"),
+        ])
+        .output()
+        .ok()?;
+
+    let pixels = output.stdout;
+
+    if pixels.len() < 9 * 8 {
+        return None;
+    }
+
+    // dHash: within each row compare a pixel to its right neighbour.
+    let mut hash = 0u64;
+    let mut bit = 0;
+
+    for y in 0..8 {
+        for x in 0..8 {
+            if pixels[y * 9 + x] > pixels[y * 9 + x + 1] {
+                hash |= 1 << bit;
+            }
+            bit += 1;
+        }
+    }
+
+    Some(hash)
+}
+
+/// Return the path of an already-seen fingerprint within the Hamming threshold.
+fn nearest_duplicate(seen: &HashMap<u64, PathBuf>, hash: u64) -> Option<&Path> {
+    seen.iter()
+        .find(|(&known, _)| (known ^ hash).count_ones() <= DEDUPE_THRESHOLD)
+        .map(|(_, path)| path.as_path())
+}
+
+/// Turn one source video into a [`ChunkGroup`] and the per-chunk [`Job`]s that
+/// feed it. Falls back to a single whole-file job when no usable cut is found.
+fn plan_chunk_jobs(
+    source: &Path,
+    output: PathBuf,
+    thresh: f64,
+    min_frames: usize,
+    verify: VerifyMode,
+    target_vmaf: Option<f64>,
+) -> Vec<Job> {
+    let input = source.to_owned();
+    let ranges = plan_chunks(&input, thresh, min_frames);
+
+    if ranges.len() <= 1 {
+        return vec![Job {
+            input,
+            output,
+            chunk: None,
+        }];
+    }
+
+    // Resolve the target-quality CRF once for the whole source so every chunk
+    // shares it; otherwise chunked mode would silently ignore --target-vmaf.
+    let crf = match target_vmaf {
+        Some(target) => select_crf(&input, target).to_string(),
+        None => String::from("30"),
+    };
+
+    let stem = output
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let ext = output
+        .extension()
+        .and_then(OsStr::to_str)
+        .unwrap_or("webm")
+        .to_owned();
+
+    let segments: Vec<PathBuf> = (0..ranges.len())
+        .map(|i| output.with_file_name(format!("{stem}.seg{i:04}.{ext}")))
+        .collect();
+
+    let group = Arc::new(ChunkGroup {
+        source: input.clone(),
+        output: output.clone(),
+        list: output.with_file_name(format!("{stem}.concat.txt")),
+        segments: segments.clone(),
+        remaining: Mutex::new(ranges.len()),
+        failed: Mutex::new(false),
+        verify,
+        crf,
+    });
+
+    ranges
+        .iter()
+        .enumerate()
+        .map(|(i, &(start, end))| Job {
+            input: input.clone(),
+            output: segments[i].clone(),
+            chunk: Some(Chunk {
+                group: group.clone(),
+                start,
+                end,
+            }),
+        })
+        .collect()
+}
+
 fn main() {
     let cli = Cli::parse();
 
@@ -95,7 +962,7 @@ fn main() {
 
     let mut threads = Vec::with_capacity(core);
 
-    let arg_mode = if cli.args.is_some() {
+    let arg_mode = if cli.args.is_some() || cli.config.is_some() {
         ArgMode::Custom
     } else if cli.video {
         ArgMode::Video
@@ -103,12 +970,52 @@ fn main() {
         ArgMode::Image
     };
 
-    let use_extension = if cli.video { "webm" } else { "webp" };
+    let fallback_ext = if cli.video { "webm" } else { "webp" };
+
+    let custom = Arc::new(
+        match CustomConfig::load(cli.args.as_deref(), cli.config.as_ref(), fallback_ext) {
+            Ok(custom) => custom,
+            Err(error) => {
+                eprintln!("{error}");
+                std::process::exit(1);
+            }
+        },
+    );
+
+    // Live bars only make sense on a real terminal; CI and `--no-progress`
+    // keep the plain start/end logging.
+    let progress = !cli.no_progress && std::io::stderr().is_terminal();
 
-    for _ in 0..core {
+    let multi = Arc::new(MultiProgress::new());
+
+    let worker_style = ProgressStyle::with_template(
+        "{prefix:>8} [{bar:30}] {percent:>3}% {wide_msg}",
+    )
+    .unwrap()
+    .progress_chars("=> ");
+
+    let total_bar = Arc::new(multi.add(ProgressBar::new(0)));
+    total_bar.set_style(
+        ProgressStyle::with_template("{prefix:>8} [{bar:30}] {pos}/{len} files")
+            .unwrap()
+            .progress_chars("=> "),
+    );
+    total_bar.set_prefix("total");
+
+    for id in 0..core {
         let pair = pair.clone();
+        let custom = custom.clone();
         let dry = cli.dry;
         let video = cli.video;
+        let target_vmaf = cli.target_vmaf;
+        let metadata = cli.metadata;
+        let verify = cli.verify;
+        let total_bar = total_bar.clone();
+        let multi = multi.clone();
+
+        let worker_bar = multi.add(ProgressBar::new(0));
+        worker_bar.set_style(worker_style.clone());
+        worker_bar.set_prefix(format!("job {id}"));
 
         let thread = std::thread::spawn(move || {
             loop {
@@ -121,7 +1028,10 @@ fn main() {
 
                     loop {
                         if state.cancel || (state.done && state.jobs.len() == 0) {
-                            println!("Thread quit!");
+                            worker_bar.finish_and_clear();
+                            if !progress {
+                                println!("Thread quit!");
+                            }
                             return;
                         }
 
@@ -140,23 +1050,43 @@ fn main() {
                         OsStr::new("-hide_banner"),
                         OsStr::new("-n"),
                         OsStr::new("-i"),
-                        work.0.as_os_str(),
+                        work.input.as_os_str(),
                         OsStr::new("-vcodec"),
                         OsStr::new("libwebp"),
                         OsStr::new("-qscale"),
                         OsStr::new("80"),
-                        work.1.as_os_str(),
+                        work.output.as_os_str(),
                     ];
 
+                    // Pick the CRF per video when a VMAF target is requested,
+                    // otherwise keep the historical default of 30.
+                    let crf = if video && work.chunk.is_none() && dry == 0 {
+                        if let Some(target) = target_vmaf {
+                            let crf = select_crf(&work.input, target);
+                            let mut file = pair.2.lock().unwrap();
+                            writeln!(
+                                file,
+                                "# target-vmaf {target} -> crf {crf} for {:?}",
+                                work.input
+                            )
+                            .unwrap();
+                            crf.to_string()
+                        } else {
+                            String::from("30")
+                        }
+                    } else {
+                        String::from("30")
+                    };
+
                     let arg_video = [
                         OsStr::new("-hide_banner"),
                         OsStr::new("-n"),
                         OsStr::new("-i"),
-                        work.0.as_os_str(),
+                        work.input.as_os_str(),
                         OsStr::new("-c:v"),
                         OsStr::new("libvpx-vp9"),
                         OsStr::new("-crf"),
-                        OsStr::new("30"),
+                        OsStr::new(&crf),
                         OsStr::new("-b:v"),
                         OsStr::new("0"),
                         OsStr::new("-b:a"),
@@ -165,17 +1095,47 @@ fn main() {
                         OsStr::new("libopus"),
                         OsStr::new("-row-mt"),
                         OsStr::new("1"),
-                        work.1.as_os_str(),
+                        work.output.as_os_str(),
                     ];
 
-                    let args = match arg_mode {
-                        ArgMode::Image => arg_image.as_slice(),
-                        ArgMode::Video => arg_video.as_slice(),
+                    let custom_args;
+                    let chunk_args;
+                    let mut args: Vec<&OsStr> = match arg_mode {
+                        _ if work.chunk.is_some() => {
+                            chunk_args = chunk_video_args(&work);
+                            chunk_args.iter().map(OsString::as_os_str).collect()
+                        }
+                        ArgMode::Image => arg_image.to_vec(),
+                        ArgMode::Video => arg_video.to_vec(),
                         ArgMode::Custom => {
-                            todo!()
+                            let ext = work
+                                .input
+                                .extension()
+                                .and_then(OsStr::to_str)
+                                .unwrap_or_default();
+
+                            let profile = custom
+                                .profile(ext)
+                                .expect("no custom profile for extension");
+
+                            custom_args = profile.build(&work.input, &work.output);
+                            custom_args.iter().map(OsString::as_os_str).collect()
                         }
                     };
 
+                    // Stream machine-readable progress on stdout when a live
+                    // display is active.
+                    if progress && video {
+                        args.splice(
+                            1..1,
+                            [
+                                OsStr::new("-progress"),
+                                OsStr::new("pipe:1"),
+                                OsStr::new("-nostats"),
+                            ],
+                        );
+                    }
+
                     if dry != 0 {
                         print!("ffmpeg");
                         for arg in args {
@@ -200,11 +1160,22 @@ fn main() {
                             Ok(())
                         });
 
-                        if video {
-                            println!("Start {:?} {:?}", work.0, work.1);
+                        if video && !progress {
+                            println!("Start {:?} {:?}", work.input, work.output);
                         }
 
-                        let status = if video {
+                        let status = if progress && video {
+                            let duration = match &work.chunk {
+                                Some(Chunk { start, end: Some(end), .. }) => end - start,
+                                chunk => {
+                                    ffprobe_entry(&work.input, false, "duration").unwrap_or(0.0)
+                                        - chunk.as_ref().map_or(0.0, |c| c.start)
+                                }
+                            };
+                            worker_bar.reset();
+                            worker_bar.set_message(format!("{:?}", work.input));
+                            run_with_progress(command, &worker_bar, duration)
+                        } else if video {
                             command.spawn().unwrap().wait_with_output().unwrap()
                         } else {
                             command.output().unwrap()
@@ -217,20 +1188,48 @@ fn main() {
                         }
 
                         if status.status.success() {
-                            if video {
-                                print!("End: ")
+                            if progress {
+                                multi.suspend(|| {
+                                    println!("{:?} {:?}", work.input, work.output);
+                                });
+                            } else {
+                                if video {
+                                    print!("End: ")
+                                }
+
+                                println!("{:?} {:?}", work.input, work.output);
                             }
 
-                            println!("{:?} {:?}", work.0, work.1);
-                            std::fs::remove_file(work.0).unwrap();
+                            if let Some(chunk) = &work.chunk {
+                                complete_chunk(chunk, true);
+                            } else if verify_output(verify, &work.input, &work.output) {
+                                if let (false, Some(mode)) = (video, metadata) {
+                                    apply_metadata(mode, &work.input, &work.output);
+                                }
+                                std::fs::remove_file(work.input).unwrap();
+                            } else {
+                                eprintln!(
+                                    "Verification failed, keeping source {:?}",
+                                    work.input
+                                );
+                                std::fs::remove_file(&work.output).ok();
+                            }
                         } else {
-                            eprintln!("Failed: {:?} {:?}", work.0, work.1);
+                            eprintln!("Failed: {:?} {:?}", work.input, work.output);
                             std::io::stderr().write_all(&status.stderr).unwrap();
 
                             if video {
-                                std::fs::remove_file(work.1).unwrap();
+                                std::fs::remove_file(&work.output).ok();
+                            }
+
+                            // A failed chunk still has to be reported to its
+                            // group so the finalizer runs and nothing leaks.
+                            if let Some(chunk) = &work.chunk {
+                                complete_chunk(chunk, false);
                             }
                         }
+
+                        total_bar.inc(1);
                     }
                 }
             }
@@ -240,6 +1239,7 @@ fn main() {
     }
 
     let mut outputs = HashSet::new();
+    let mut fingerprints: HashMap<u64, PathBuf> = HashMap::new();
 
     'outer: for entry in WalkDir::new(cli.folder)
         .into_iter()
@@ -252,13 +1252,46 @@ fn main() {
             continue;
         };
 
-        if exts.iter().any(|&e| e.eq_ignore_ascii_case(ext)) {
+        let base_match = exts.iter().any(|&e| e.eq_ignore_ascii_case(ext));
+
+        let matches = match arg_mode {
+            ArgMode::Custom => {
+                custom.profiles.contains_key(&ext.to_ascii_lowercase())
+                    || (custom.default.is_some() && base_match)
+            }
+            _ => base_match,
+        };
+
+        if matches {
             if pair.0.lock().unwrap().cancel {
                 break 'outer;
             }
 
+            let fingerprint = if cli.dedupe {
+                perceptual_hash(path, cli.video)
+            } else {
+                None
+            };
+
+            if let Some(hash) = fingerprint {
+                match nearest_duplicate(&fingerprints, hash) {
+                    Some(original) => {
+                        println!("Duplicate {:?} of {:?}, skipping", path, original);
+                        continue;
+                    }
+                    None => {
+                        fingerprints.insert(hash, path.to_owned());
+                    }
+                }
+            }
+
             let (mutex, cond, _) = &*(pair);
 
+            let use_extension = match arg_mode {
+                ArgMode::Custom => custom.out_ext(ext, fallback_ext),
+                _ => fallback_ext,
+            };
+
             let mut counter = 0;
             let mut output = path.with_extension(use_extension);
 
@@ -279,18 +1312,42 @@ fn main() {
 
             outputs.insert(output.clone());
 
-            mutex
-                .lock()
-                .unwrap()
-                .jobs
-                .push_back((path.to_owned(), output));
+            let chunked = cli.chunks && matches!(arg_mode, ArgMode::Video);
+
+            let jobs = if chunked {
+                plan_chunk_jobs(
+                    path,
+                    output,
+                    cli.scene_thresh,
+                    cli.chunk_frames,
+                    cli.verify,
+                    cli.target_vmaf,
+                )
+            } else {
+                vec![Job {
+                    input: path.to_owned(),
+                    output,
+                    chunk: None,
+                }]
+            };
+
+            total_bar.inc_length(jobs.len() as u64);
+
+            {
+                let mut state = mutex.lock().unwrap();
+                for job in jobs {
+                    state.jobs.push_back(job);
+                }
+            }
 
             cond.notify_all();
         }
     }
 
-    println!("Done search!");
-    println!("Jobs {} left", pair.0.lock().unwrap().jobs.len());
+    if !progress {
+        println!("Done search!");
+        println!("Jobs {} left", pair.0.lock().unwrap().jobs.len());
+    }
 
     pair.0.lock().unwrap().done = true;
     pair.1.notify_all();
@@ -299,7 +1356,11 @@ fn main() {
         thread.join().unwrap();
     }
 
-    println!("Jobs {} left", pair.0.lock().unwrap().jobs.len());
+    total_bar.finish();
+
+    if !progress {
+        println!("Jobs {} left", pair.0.lock().unwrap().jobs.len());
+    }
 
     pair.2.lock().unwrap().flush().unwrap();
 
@@ -329,5 +1390,42 @@ struct Cli {
     #[arg(default_value_t = false, short = 'v')]
     video: bool,
 
+    /// TOML file of per-extension custom profiles (`[png] template = "…"`).
+    #[arg(short = 'c')]
+    config: Option<PathBuf>,
+
+    /// Split each video into scene-cut chunks encoded in parallel.
+    #[arg(default_value_t = false, short = 'C', long)]
+    chunks: bool,
+
+    /// Scene-change detection threshold for `--chunks` (0.0..1.0).
+    #[arg(default_value_t = 0.3, long)]
+    scene_thresh: f64,
+
+    /// Minimum frames per chunk for `--chunks`.
+    #[arg(default_value_t = 240, long)]
+    chunk_frames: usize,
+
+    /// Auto-pick the CRF per video to hit this VMAF score (0..100).
+    #[arg(long)]
+    target_vmaf: Option<f64>,
+
+    /// Keep or strip image metadata (EXIF/ICC/XMP) around the transcode.
+    #[arg(long, value_enum)]
+    metadata: Option<MetadataMode>,
+
+    /// Skip inputs whose perceptual hash matches an already-seen file.
+    #[arg(default_value_t = false, long)]
+    dedupe: bool,
+
+    /// Disable live progress bars and keep plain start/end logging.
+    #[arg(default_value_t = false, long)]
+    no_progress: bool,
+
+    /// How strictly to verify the output before deleting the source.
+    #[arg(default_value = "exists", long, value_enum)]
+    verify: VerifyMode,
+
+    /// Custom ffmpeg argument template with `{input}`/`{output}` placeholders.
     args: Option<String>,
 }